@@ -3,7 +3,7 @@
 //! ## Summary
 //!
 //! * Crates can declare variables that can be overridden
-//!     * Anything const, e.g. usize, strings, etc.
+//!     * Anything const, e.g. usize, strings, arrays, etc.
 //! * (Only) The "root crate" can override these variables by including a `cfg.toml` file
 //!
 //! ## Config file
@@ -110,23 +110,94 @@
 //!
 //! error: could not compile `failing-config` (lib) due to 1 previous error
 //! ```
+//!
+//! ### `include`-ing other config files
+//!
+//! A `cfg.toml` can pull in other files before applying its own overrides, similar to Cargo's
+//! config-include feature. Paths are resolved relative to the file declaring them, and are
+//! applied left-to-right, with the including file's own values always winning:
+//!
+//! ```toml
+//! # cfg.toml
+//! include = "boards/esp32.toml"
+//!
+//! [lib-one]
+//! buffer_size = 4096
+//! ```
+//!
+//! ### Environment variable overrides
+//!
+//! Following Cargo's config env convention, each field can also be overridden by an env var read
+//! at macro expansion time, taking precedence over both the `cfg.toml` value and `#[default(...)]`
+//! / `#[required]`. The variable name is `TOML_CFG_<CARGO_PKG_NAME>_<FIELD>`, uppercased with
+//! dashes replaced by underscores:
+//!
+//! ```shell
+//! $ TOML_CFG_LIB_ONE_BUFFER_SIZE=8192 cargo build --quiet
+//! ```
+//!
+//! This is handy in CI, for secrets you don't want committed to a `cfg.toml` (e.g. a
+//! `wifi_passkey`), and for one-off tweaks without editing files.
+//!
+//! ### Workspace config hierarchy
+//!
+//! Like Cargo's own config files, `cfg.toml` is looked for at every directory level between the
+//! crate being compiled and the workspace root, and all of the files found are merged, with the
+//! file closest to the compiled crate winning on a per-key basis. This lets a workspace define
+//! shared defaults in a top-level `cfg.toml`, while individual member crates override just the
+//! keys they care about in their own directory.
+//!
+//! ### Inline override via `TOML_CFG_INLINE`
+//!
+//! For one-off builds and scripts, a whole TOML snippet can be passed through a single env var,
+//! matching the ergonomics of Cargo's `--config key=value`. It's parsed the same way as a
+//! `cfg.toml` file and always wins over whatever was found on disk:
+//!
+//! ```shell
+//! $ TOML_CFG_INLINE='[lib-one]
+//! buffer_size = 256' cargo build --quiet
+//! ```
 
 use heck::ToShoutySnekCase;
 use proc_macro::TokenStream;
-use proc_macro2::TokenStream as TokenStream2;
+use proc_macro2::{Literal, TokenStream as TokenStream2};
 use quote::{quote, ToTokens};
 use serde::Deserialize;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::env;
 use std::path::{Path, PathBuf};
 use syn::Expr;
 
 #[derive(Deserialize, Clone, Debug)]
 struct Config {
+    /// `include = "other.toml"` or `include = ["a.toml", "b.toml"]`, resolved relative to the
+    /// file declaring them. Processed left-to-right, with later entries overriding earlier ones
+    /// and the including file's own values taking precedence over all of them.
+    #[serde(default)]
+    include: Include,
     #[serde(flatten)]
     crates: HashMap<String, Defn>,
 }
 
+#[derive(Deserialize, Clone, Debug, Default)]
+#[serde(untagged)]
+enum Include {
+    #[default]
+    None,
+    One(String),
+    Many(Vec<String>),
+}
+
+impl Include {
+    fn paths(&self) -> Vec<&str> {
+        match self {
+            Include::None => Vec::new(),
+            Include::One(path) => vec![path.as_str()],
+            Include::Many(paths) => paths.iter().map(String::as_str).collect(),
+        }
+    }
+}
+
 #[derive(Deserialize, Clone, Debug, Default)]
 struct Defn {
     #[serde(flatten)]
@@ -139,17 +210,56 @@ pub fn toml_config(_attr: TokenStream, item: TokenStream) -> TokenStream {
         syn::parse::<syn::ItemStruct>(item).expect("Failed to parse configuration structure!");
 
     let require_cfg_present = env::var("TOML_CFG").is_ok_and(|v| v.contains("require_cfg_present"));
+    let pkg_name = env::var("CARGO_PKG_NAME").unwrap_or_default();
 
-    let cfg_path = find_root_path().map(|c| c.join("cfg.toml"));
-    let maybe_cfg = cfg_path.as_ref().and_then(|c| load_crate_cfg(c));
+    // Mirror Cargo's own config hierarchy: walk from the crate being compiled up towards the
+    // workspace/target root, merging every `cfg.toml` found along the way so the file closest to
+    // the compiled crate wins on a per-key basis.
+    let cfg_paths = find_root_path()
+        .map(|root| find_cfg_paths(&root))
+        .unwrap_or_default();
 
-    if require_cfg_present && maybe_cfg.is_none() {
+    let mut merged_cfg = Defn::default();
+    let mut merged_touched = Vec::new();
+    let mut any_cfg_found = false;
+    for cfg_path in &cfg_paths {
+        if let Some((defn, touched)) = load_crate_cfg(cfg_path) {
+            any_cfg_found = true;
+            merged_cfg.vals.extend(defn.vals);
+            merged_touched.extend(touched);
+        }
+    }
+
+    // `TOML_CFG_INLINE` is a whole TOML snippet, parsed the same way as a `cfg.toml` file, and
+    // always wins over anything found on disk.
+    if let Ok(inline) = env::var("TOML_CFG_INLINE") {
+        let parsed = toml::from_str::<Config>(&inline).unwrap_or_else(|e| {
+            panic!(
+                "Failed to parse TOML_CFG_INLINE as a toml-cfg config: {}",
+                e
+            )
+        });
+        if let Some(inline_section) = parsed.crates.get(&pkg_name) {
+            any_cfg_found = true;
+            merged_cfg.vals.extend(inline_section.vals.clone());
+        }
+    }
+
+    let loaded = any_cfg_found.then_some((merged_cfg, merged_touched));
+
+    if require_cfg_present && loaded.is_none() {
         panic!("TOML_CFG=require_cfg_present set, but valid config not found!")
     }
-    let cfg = maybe_cfg.unwrap_or_default();
+    let cfg = loaded
+        .as_ref()
+        .map(|(defn, _)| defn.clone())
+        .unwrap_or_default();
 
     let mut struct_defn_fields = TokenStream2::new();
     let mut struct_inst_fields = TokenStream2::new();
+    // Every override env var we consult, whether or not it was actually set, so we can retrigger
+    // recompilation if its value ever changes.
+    let mut consulted_env_vars: Vec<String> = vec!["TOML_CFG_INLINE".to_string()];
 
     for field in struct_defn.fields {
         let ident = field
@@ -167,23 +277,32 @@ pub fn toml_config(_attr: TokenStream, item: TokenStream) -> TokenStream {
             );
         }
 
-        // Is this field provided by the config file?
-        let val = match cfg.vals.get(&ident.to_string()) {
-            Some(t) => {
-                let t_string = t.to_string();
-                syn::parse_str::<Expr>(&t_string)
-                    .unwrap_or_else(|_| panic!("Field `{}`: failed to parse `{}` as a valid token!", ident, &t_string))
-            }
+        // An env var always wins over the config file, following Cargo's config env convention.
+        let env_var_name = format!(
+            "TOML_CFG_{}_{}",
+            pkg_name.TO_SHOUTY_SNEK_CASE(),
+            ident.to_string().TO_SHOUTY_SNEK_CASE(),
+        );
+        consulted_env_vars.push(env_var_name.clone());
+        let env_value = env::var(&env_var_name)
+            .ok()
+            .map(|raw| parse_env_value(&raw));
+
+        let ty = field.ty;
+
+        // Is this field provided by an env var, then the config file?
+        let val: TokenStream2 = match env_value.as_ref().or(cfg.vals.get(&ident.to_string())) {
+            Some(t) => value_to_tokens(&ident, &ty, t),
             None => match (default_attribute, required_attribute) {
                 (Some(default), None) => {
-                    default.parse_args().unwrap_or_else(|e| panic!("Field `{}`: failed to parse default value: {}", ident, e))
+                    let default_expr: Expr = default.parse_args().unwrap_or_else(|e| panic!("Field `{}`: failed to parse default value: {}", ident, e));
+                    quote! { #default_expr }
                 },
                 (None, Some(_)) => panic!("Field `{}`: required but no value was provided in the config file.", ident),
                 _ => panic!("Field `{}`: expected exactly one of `#[required]` or `#[default(...)]` to be provided.", ident),
             },
         };
 
-        let ty = field.ty;
         quote! {
             pub #ident: #ty,
         }
@@ -202,14 +321,24 @@ pub fn toml_config(_attr: TokenStream, item: TokenStream) -> TokenStream {
         .parse()
         .expect("NO NOT THE SHOUTY SNAKE");
 
-    let hack_retrigger = if let Some(cfg_path) = cfg_path {
-        let cfg_path = format!("{}", cfg_path.display());
+    let mut hack_retrigger = TokenStream2::new();
+    if let Some((_, touched_paths)) = &loaded {
+        for touched_path in touched_paths {
+            let touched_path = format!("{}", touched_path.display());
+            quote! {
+                const _: &[u8] = include_bytes!(#touched_path);
+            }
+            .to_tokens(&mut hack_retrigger);
+        }
+    }
+    for env_var_name in &consulted_env_vars {
+        // `option_env!` is tracked by rustc for recompilation the same way `include_bytes!` is,
+        // so a later `cargo build` picks up a changed (or newly set/unset) env var.
         quote! {
-            const _: &[u8] = include_bytes!(#cfg_path);
+            const _: Option<&str> = option_env!(#env_var_name);
         }
-    } else {
-        quote! {}
-    };
+        .to_tokens(&mut hack_retrigger);
+    }
 
     quote! {
         pub struct #struct_ident {
@@ -227,11 +356,188 @@ pub fn toml_config(_attr: TokenStream, item: TokenStream) -> TokenStream {
     .into()
 }
 
-fn load_crate_cfg(path: &Path) -> Option<Defn> {
+/// Converts a `toml::Value` directly into a Rust const expression matching the field's declared
+/// type, recursing into arrays so structured fields like `ip: [u8; 4]`, `peers: &[&str]`, or
+/// `pair: (u8, u8)` populate correctly. Tables and datetimes have no Rust type to map onto, so
+/// they're rejected, as are TOML arrays whose destination type isn't one of the three above.
+fn value_to_tokens(ident: &syn::Ident, ty: &syn::Type, value: &toml::Value) -> TokenStream2 {
+    match (ty, value) {
+        (_, toml::Value::String(s)) => quote! { #s },
+        (_, toml::Value::Integer(i)) => {
+            let lit = Literal::i64_unsuffixed(*i);
+            quote! { #lit }
+        }
+        (_, toml::Value::Float(f)) => {
+            let lit = Literal::f64_unsuffixed(*f);
+            quote! { #lit }
+        }
+        (_, toml::Value::Boolean(b)) => quote! { #b },
+        (syn::Type::Array(array_ty), toml::Value::Array(items)) => {
+            if let syn::Expr::Lit(syn::ExprLit {
+                lit: syn::Lit::Int(len),
+                ..
+            }) = &array_ty.len
+            {
+                let len = len
+                    .base10_parse::<usize>()
+                    .unwrap_or_else(|e| panic!("Field `{}`: failed to parse array length: {}", ident, e));
+                if len != items.len() {
+                    panic!(
+                        "Field `{}`: array type `{}` has {} element(s) but the config array has {}",
+                        ident,
+                        quote! { #ty },
+                        len,
+                        items.len()
+                    );
+                }
+            }
+            let elems = items
+                .iter()
+                .map(|v| value_to_tokens(ident, &array_ty.elem, v));
+            quote! { [#(#elems),*] }
+        }
+        (syn::Type::Reference(ref_ty), toml::Value::Array(items)) => match &*ref_ty.elem {
+            syn::Type::Slice(slice_ty) => {
+                let elems = items
+                    .iter()
+                    .map(|v| value_to_tokens(ident, &slice_ty.elem, v));
+                quote! { &[#(#elems),*] }
+            }
+            _ => panic!(
+                "Field `{}`: unsupported array target type `{}`; expected a fixed-size array, slice reference, or tuple",
+                ident,
+                quote! { #ty }
+            ),
+        },
+        (syn::Type::Tuple(tuple_ty), toml::Value::Array(items)) => {
+            if tuple_ty.elems.len() != items.len() {
+                panic!(
+                    "Field `{}`: tuple type `{}` has {} element(s) but the config array has {}",
+                    ident,
+                    quote! { #ty },
+                    tuple_ty.elems.len(),
+                    items.len()
+                );
+            }
+            let elems = items
+                .iter()
+                .zip(tuple_ty.elems.iter())
+                .map(|(v, elem_ty)| value_to_tokens(ident, elem_ty, v));
+            quote! { (#(#elems,)*) }
+        }
+        (_, toml::Value::Array(_)) => panic!(
+            "Field `{}`: unsupported array target type `{}`; expected a fixed-size array, slice reference, or tuple",
+            ident,
+            quote! { #ty }
+        ),
+        (_, toml::Value::Table(_)) | (_, toml::Value::Datetime(_)) => {
+            panic!("Field `{}`: unsupported value kind for field", ident)
+        }
+    }
+}
+
+/// Parses a raw env var override through the same TOML value grammar the config file uses, so
+/// e.g. `4096` becomes an integer and `hi`/`"hi"` both become a string.
+fn parse_env_value(raw: &str) -> toml::Value {
+    let wrapped = format!("v = {}", raw);
+    match wrapped.parse::<toml::Value>() {
+        Ok(toml::Value::Table(t)) => t
+            .get("v")
+            .cloned()
+            .unwrap_or_else(|| toml::Value::String(raw.to_string())),
+        _ => toml::Value::String(raw.to_string()),
+    }
+}
+
+/// Loads the `[CARGO_PKG_NAME]` table from `path`, merging in any files it `include`s.
+///
+/// Returns the merged `Defn` alongside every file that was actually read, in the order it was
+/// read, so the caller can retrigger recompilation if any of them change.
+fn load_crate_cfg(path: &Path) -> Option<(Defn, Vec<PathBuf>)> {
+    let mut visited = HashSet::new();
+    let mut touched = Vec::new();
+    let defn = load_crate_cfg_inner(path, &mut visited, &mut touched)?;
+    Some((defn, touched))
+}
+
+fn load_crate_cfg_inner(
+    path: &Path,
+    visited: &mut HashSet<PathBuf>,
+    touched: &mut Vec<PathBuf>,
+) -> Option<Defn> {
+    let canonical = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+    if !visited.insert(canonical.clone()) {
+        panic!(
+            "include cycle detected: `{}` includes itself, directly or indirectly",
+            path.display()
+        );
+    }
+
+    // `canonical` must come back out of `visited` on every exit path below (a missing or
+    // unparseable include is not a cycle), so the early returns live in a helper instead of
+    // this function directly.
+    let result = load_crate_cfg_body(path, visited, touched);
+
+    visited.remove(&canonical);
+
+    result
+}
+
+fn load_crate_cfg_body(
+    path: &Path,
+    visited: &mut HashSet<PathBuf>,
+    touched: &mut Vec<PathBuf>,
+) -> Option<Defn> {
     let contents = std::fs::read_to_string(path).ok()?;
     let parsed = toml::from_str::<Config>(&contents).ok()?;
+    touched.push(path.to_path_buf());
+
     let name = env::var("CARGO_PKG_NAME").ok()?;
-    parsed.crates.get(&name).cloned()
+    let dir = path.parent().unwrap_or_else(|| Path::new("."));
+
+    let mut merged = Defn::default();
+    let mut found = false;
+
+    // Includes are processed left-to-right, so later entries override earlier ones.
+    for include in parsed.include.paths() {
+        let resolved = dir.join(include);
+        if let Some(included) = load_crate_cfg_inner(&resolved, visited, touched) {
+            found = true;
+            merged.vals.extend(included.vals);
+        }
+    }
+
+    // The including file's own values take precedence over anything it pulled in.
+    if let Some(local) = parsed.crates.get(&name) {
+        found = true;
+        merged.vals.extend(local.vals.clone());
+    }
+
+    found.then_some(merged)
+}
+
+/// Walks from the crate being compiled (`CARGO_MANIFEST_DIR`) up towards `root`, listing the
+/// path to a would-be `cfg.toml` at every level in between. The root-most path is listed first
+/// and the crate's own directory last, so merging them in order lets the closest file win.
+/// A crate that does not live under `root` (e.g. a registry dependency) only gets `root`'s file.
+fn find_cfg_paths(root: &Path) -> Vec<PathBuf> {
+    let Ok(manifest_dir) = env::var("CARGO_MANIFEST_DIR") else {
+        return vec![root.join("cfg.toml")];
+    };
+
+    let mut dir = PathBuf::from(manifest_dir);
+    if !dir.starts_with(root) {
+        return vec![root.join("cfg.toml")];
+    }
+    let mut paths = vec![dir.join("cfg.toml")];
+    while dir != root {
+        if !dir.pop() {
+            break;
+        }
+        paths.push(dir.join("cfg.toml"));
+    }
+    paths.reverse();
+    paths
 }
 
 // From https://stackoverflow.com/q/60264534